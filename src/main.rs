@@ -1,6 +1,14 @@
-use std::net::UdpSocket;
-use std::io::Result;
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::io::{Read, Result, Write};
 use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Maximum size of a UDP response before we truncate it and set TC,
+// signaling the client to retry over TCP (RFC 1035 section 4.2.1).
+const MAX_UDP_RESPONSE_SIZE: usize = 512;
 
 // DNS Header structure
 #[derive(Debug)]
@@ -49,6 +57,399 @@ struct DnsQuestion {
     qclass: u16,
 }
 
+// Response codes we produce (RFC 1035 section 4.1.1)
+const RCODE_NO_ERROR: u8 = 0;
+const RCODE_SERVER_FAILURE: u8 = 2;
+const RCODE_NAME_ERROR: u8 = 3;
+
+// Named view of the header's flags field (RFC 1035 section 4.1.1):
+//
+//  1  1  1  1  1  1
+//  5  4  3  2  1  0  9  8  7  6  5  4  3  2  1  0
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+// |QR|   Opcode  |AA|TC|RD|RA|   Z    |   RCODE   |
+// +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+#[derive(Debug, Clone, Copy, Default)]
+struct DnsFlags {
+    qr: bool,
+    opcode: u8,
+    aa: bool,
+    tc: bool,
+    rd: bool,
+    ra: bool,
+    z: u8,
+    rcode: u8,
+}
+
+impl DnsFlags {
+    fn from_u16(value: u16) -> Self {
+        DnsFlags {
+            qr: (value >> 15) & 0x1 == 1,
+            opcode: ((value >> 11) & 0x0F) as u8,
+            aa: (value >> 10) & 0x1 == 1,
+            tc: (value >> 9) & 0x1 == 1,
+            rd: (value >> 8) & 0x1 == 1,
+            ra: (value >> 7) & 0x1 == 1,
+            z: ((value >> 4) & 0x07) as u8,
+            rcode: (value & 0x0F) as u8,
+        }
+    }
+
+    fn to_u16(self) -> u16 {
+        let mut value: u16 = 0;
+        value |= (self.qr as u16) << 15;
+        value |= ((self.opcode & 0x0F) as u16) << 11;
+        value |= (self.aa as u16) << 10;
+        value |= (self.tc as u16) << 9;
+        value |= (self.rd as u16) << 8;
+        value |= (self.ra as u16) << 7;
+        value |= ((self.z & 0x07) as u16) << 4;
+        value |= (self.rcode & 0x0F) as u16;
+        value
+    }
+}
+
+// Record type numbers we understand (RFC 1035 section 3.2.2)
+const TYPE_A: u16 = 1;
+const TYPE_NS: u16 = 2;
+const TYPE_CNAME: u16 = 5;
+const TYPE_MX: u16 = 15;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SOA: u16 = 6;
+const TYPE_OPT: u16 = 41;
+
+// UDP payload size we advertise in our own EDNS0 OPT record.
+const OUR_EDNS_UDP_SIZE: u16 = 4096;
+
+// RDATA for a single resource record. Each implementation serializes its
+// own type-specific payload straight into the outgoing packet; the
+// surrounding RR framing (NAME/TYPE/CLASS/TTL/RDLENGTH) is written by the
+// caller. Domain names embedded in RDATA go through `names` so they can be
+// compressed against names already written earlier in the packet.
+trait RData: Send + Sync {
+    fn rtype(&self) -> u16;
+    fn write_rdata(&self, response: &mut Vec<u8>, names: &mut NameWriter);
+}
+
+struct ARecord(Ipv4Addr);
+
+impl RData for ARecord {
+    fn rtype(&self) -> u16 {
+        TYPE_A
+    }
+
+    fn write_rdata(&self, response: &mut Vec<u8>, _names: &mut NameWriter) {
+        response.extend_from_slice(&self.0.octets());
+    }
+}
+
+struct AaaaRecord(Ipv6Addr);
+
+impl RData for AaaaRecord {
+    fn rtype(&self) -> u16 {
+        TYPE_AAAA
+    }
+
+    fn write_rdata(&self, response: &mut Vec<u8>, _names: &mut NameWriter) {
+        response.extend_from_slice(&self.0.octets());
+    }
+}
+
+struct CnameRecord(String);
+
+impl RData for CnameRecord {
+    fn rtype(&self) -> u16 {
+        TYPE_CNAME
+    }
+
+    fn write_rdata(&self, response: &mut Vec<u8>, names: &mut NameWriter) {
+        names.write(&self.0, response);
+    }
+}
+
+struct NsRecord(String);
+
+impl RData for NsRecord {
+    fn rtype(&self) -> u16 {
+        TYPE_NS
+    }
+
+    fn write_rdata(&self, response: &mut Vec<u8>, names: &mut NameWriter) {
+        names.write(&self.0, response);
+    }
+}
+
+struct MxRecord {
+    preference: u16,
+    exchange: String,
+}
+
+impl RData for MxRecord {
+    fn rtype(&self) -> u16 {
+        TYPE_MX
+    }
+
+    fn write_rdata(&self, response: &mut Vec<u8>, names: &mut NameWriter) {
+        response.extend_from_slice(&self.preference.to_be_bytes());
+        names.write(&self.exchange, response);
+    }
+}
+
+struct TxtRecord(Vec<String>);
+
+impl RData for TxtRecord {
+    fn rtype(&self) -> u16 {
+        TYPE_TXT
+    }
+
+    fn write_rdata(&self, response: &mut Vec<u8>, _names: &mut NameWriter) {
+        // Each TXT character-string is length-prefixed with a single byte
+        // (RFC 1035 section 3.3.14), so split anything longer than 255 bytes
+        // into multiple character-strings rather than truncating the prefix.
+        for chunk in &self.0 {
+            for piece in chunk.as_bytes().chunks(255) {
+                response.push(piece.len() as u8);
+                response.extend_from_slice(piece);
+            }
+        }
+    }
+}
+
+// SOA (start of authority) RDATA: RFC 1035 section 3.3.13.
+struct SoaRecord {
+    m_name: String,
+    r_name: String,
+    serial: u32,
+    refresh: u32,
+    retry: u32,
+    expire: u32,
+    minimum: u32,
+}
+
+impl RData for SoaRecord {
+    fn rtype(&self) -> u16 {
+        TYPE_SOA
+    }
+
+    fn write_rdata(&self, response: &mut Vec<u8>, names: &mut NameWriter) {
+        names.write(&self.m_name, response);
+        names.write(&self.r_name, response);
+        response.extend_from_slice(&self.serial.to_be_bytes());
+        response.extend_from_slice(&self.refresh.to_be_bytes());
+        response.extend_from_slice(&self.retry.to_be_bytes());
+        response.extend_from_slice(&self.expire.to_be_bytes());
+        response.extend_from_slice(&self.minimum.to_be_bytes());
+    }
+}
+
+// Configured records, keyed by owner name and query type so a name can
+// hold several record types at once (e.g. both MX and TXT). Each record
+// carries its own TTL, as set in the zone file.
+type RecordStore = HashMap<(String, u16), Vec<(u32, Box<dyn RData>)>>;
+
+// An authoritative zone loaded from a zone file: its SOA fields plus the
+// records it holds. `domain` is the zone apex, with no trailing dot.
+struct Zone {
+    domain: String,
+    m_name: String,
+    r_name: String,
+    serial: u32,
+    refresh: u32,
+    retry: u32,
+    expire: u32,
+    minimum: u32,
+    records: RecordStore,
+}
+
+impl Zone {
+    fn soa(&self) -> SoaRecord {
+        SoaRecord {
+            m_name: self.m_name.clone(),
+            r_name: self.r_name.clone(),
+            serial: self.serial,
+            refresh: self.refresh,
+            retry: self.retry,
+            expire: self.expire,
+            minimum: self.minimum,
+        }
+    }
+}
+
+// Find the zone that is authoritative for `name`, i.e. the longest
+// configured zone domain that is `name` itself or a parent of it.
+fn find_zone<'a>(zones: &'a [Zone], name: &str) -> Option<&'a Zone> {
+    zones
+        .iter()
+        .filter(|zone| name == zone.domain || name.ends_with(&format!(".{}", zone.domain)))
+        .max_by_key(|zone| zone.domain.len())
+}
+
+// Resolve a zone-file name token to a fully qualified name (no trailing
+// dot): "@" means the zone apex, a trailing "." means already-qualified,
+// anything else is relative to $ORIGIN.
+fn qualify_name(token: &str, origin: &str) -> String {
+    if token == "@" {
+        origin.trim_end_matches('.').to_string()
+    } else if let Some(stripped) = token.strip_suffix('.') {
+        stripped.to_string()
+    } else {
+        format!("{}.{}", token, origin.trim_end_matches('.'))
+    }
+}
+
+// Load zones from a simple BIND-like zone file: lines of
+// `name [TTL] [CLASS] TYPE rdata...`, `$ORIGIN`/`$TTL` directives, `;`
+// comments, and `@` for the zone apex. A new SOA record starts a new zone.
+fn load_zones(path: &str) -> std::io::Result<Vec<Zone>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(parse_zone_file(&contents))
+}
+
+fn parse_zone_file(contents: &str) -> Vec<Zone> {
+    let mut zones = Vec::new();
+    let mut origin = String::new();
+    let mut default_ttl: u32 = 3600;
+    let mut current: Option<Zone> = None;
+
+    for raw_line in contents.lines() {
+        let line = match raw_line.split(';').next() {
+            Some(before_comment) => before_comment.trim(),
+            None => raw_line.trim(),
+        };
+        if line.is_empty() {
+            continue;
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        if tokens[0].eq_ignore_ascii_case("$ORIGIN") {
+            if let Some(value) = tokens.get(1) {
+                origin = value.to_string();
+            }
+            continue;
+        }
+        if tokens[0].eq_ignore_ascii_case("$TTL") {
+            if let Some(value) = tokens.get(1).and_then(|v| v.parse().ok()) {
+                default_ttl = value;
+            }
+            continue;
+        }
+
+        if tokens.len() < 3 {
+            continue;
+        }
+
+        let name = qualify_name(tokens[0], &origin);
+        let mut idx = 1;
+        let ttl = match tokens[idx].parse::<u32>() {
+            Ok(v) => {
+                idx += 1;
+                v
+            }
+            Err(_) => default_ttl,
+        };
+        if tokens.get(idx).map(|t| t.eq_ignore_ascii_case("IN")).unwrap_or(false) {
+            idx += 1;
+        }
+        let Some(rtype_token) = tokens.get(idx) else { continue };
+        idx += 1;
+        let rdata_tokens = &tokens[idx..];
+
+        match rtype_token.to_ascii_uppercase().as_str() {
+            "SOA" if rdata_tokens.len() >= 7 => {
+                if let Some(zone) = current.take() {
+                    zones.push(zone);
+                }
+                current = Some(Zone {
+                    domain: name,
+                    m_name: qualify_name(rdata_tokens[0], &origin),
+                    r_name: qualify_name(rdata_tokens[1], &origin),
+                    serial: rdata_tokens[2].parse().unwrap_or(0),
+                    refresh: rdata_tokens[3].parse().unwrap_or(0),
+                    retry: rdata_tokens[4].parse().unwrap_or(0),
+                    expire: rdata_tokens[5].parse().unwrap_or(0),
+                    minimum: rdata_tokens[6].parse().unwrap_or(0),
+                    records: HashMap::new(),
+                });
+            }
+            "A" if !rdata_tokens.is_empty() => {
+                if let (Some(zone), Ok(addr)) = (current.as_mut(), rdata_tokens[0].parse::<Ipv4Addr>()) {
+                    zone.records.entry((name, TYPE_A)).or_default().push((ttl, Box::new(ARecord(addr))));
+                }
+            }
+            "AAAA" if !rdata_tokens.is_empty() => {
+                if let (Some(zone), Ok(addr)) = (current.as_mut(), rdata_tokens[0].parse::<Ipv6Addr>()) {
+                    zone.records.entry((name, TYPE_AAAA)).or_default().push((ttl, Box::new(AaaaRecord(addr))));
+                }
+            }
+            "CNAME" if !rdata_tokens.is_empty() => {
+                if let Some(zone) = current.as_mut() {
+                    let target = qualify_name(rdata_tokens[0], &origin);
+                    zone.records.entry((name, TYPE_CNAME)).or_default().push((ttl, Box::new(CnameRecord(target))));
+                }
+            }
+            "NS" if !rdata_tokens.is_empty() => {
+                if let Some(zone) = current.as_mut() {
+                    let target = qualify_name(rdata_tokens[0], &origin);
+                    zone.records.entry((name, TYPE_NS)).or_default().push((ttl, Box::new(NsRecord(target))));
+                }
+            }
+            "MX" if rdata_tokens.len() >= 2 => {
+                if let (Some(zone), Ok(preference)) = (current.as_mut(), rdata_tokens[0].parse::<u16>()) {
+                    let exchange = qualify_name(rdata_tokens[1], &origin);
+                    zone.records.entry((name, TYPE_MX)).or_default().push((ttl, Box::new(MxRecord { preference, exchange })));
+                }
+            }
+            "TXT" if !rdata_tokens.is_empty() => {
+                if let Some(zone) = current.as_mut() {
+                    let text = rdata_tokens.join(" ");
+                    let text = text.trim_matches('"').to_string();
+                    zone.records.entry((name, TYPE_TXT)).or_default().push((ttl, Box::new(TxtRecord(vec![text]))));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(zone) = current.take() {
+        zones.push(zone);
+    }
+
+    zones
+}
+
+// Controls whether queries for names we don't hold locally get forwarded
+// upstream. `upstream` is `None` when recursion is disabled (--no-recursion).
+#[derive(Clone, Copy)]
+struct ResolverConfig {
+    upstream: Option<SocketAddr>,
+}
+
+// A cached answer set for a forwarded query, keyed by (name, qtype). Expiry
+// is computed from the minimum TTL seen in the upstream response. Each
+// answer keeps its own owner name (which may differ from the cache key's
+// name across a CNAME chain) since it gets re-written into a fresh packet
+// on every cache hit.
+#[derive(Clone)]
+struct CacheEntry {
+    answers: Vec<(String, u16, Vec<u8>)>,
+    expires_at: Instant,
+}
+
+type ResolverCache = HashMap<(String, u16), CacheEntry>;
+
+// Record types whose RDATA embeds a domain name (RFC 1035 section 3.3).
+// Their cached bytes may contain a compression pointer that was only valid
+// as an absolute offset into the original upstream packet, so they can't be
+// replayed verbatim into a freshly built response; we simply don't cache
+// them, and a query whose answers are all of these types is re-forwarded
+// each time instead.
+fn rdata_embeds_name(rtype: u16) -> bool {
+    // 3/4/7/8/9/12/14 are MD, MF, MB, MG, MR, PTR and MINFO.
+    matches!(rtype, TYPE_CNAME | TYPE_NS | TYPE_MX | TYPE_SOA | 3 | 4 | 7 | 8 | 9 | 12 | 14)
+}
+
 // Parse domain name from DNS packet
 fn parse_domain_name(buffer: &[u8], offset: &mut usize) -> Option<String> {
     let mut parts = Vec::new();
@@ -94,19 +495,167 @@ fn parse_domain_name(buffer: &[u8], offset: &mut usize) -> Option<String> {
     Some(parts.join("."))
 }
 
-// Encode domain name to DNS format
-fn encode_domain_name(name: &str) -> Vec<u8> {
-    let mut bytes = Vec::new();
+// Tracks domain-name suffixes already written into the outgoing packet, so
+// later names can point back at them instead of repeating the labels
+// (RFC 1035 section 4.1.4). Offsets are only recorded while they still fit
+// in the 14-bit pointer field.
+struct NameWriter {
+    offsets: HashMap<String, u16>,
+}
+
+impl NameWriter {
+    fn new() -> Self {
+        NameWriter { offsets: HashMap::new() }
+    }
+
+    // Write `name`, replacing the longest suffix already seen with a
+    // pointer and recording the offsets of any newly written suffixes.
+    fn write(&mut self, name: &str, response: &mut Vec<u8>) {
+        if name.is_empty() {
+            response.push(0);
+            return;
+        }
+
+        let labels: Vec<&str> = name.split('.').collect();
+
+        let mut split_at = labels.len();
+        let mut pointer = None;
+        for start in 0..labels.len() {
+            let suffix = labels[start..].join(".");
+            if let Some(&offset) = self.offsets.get(&suffix) {
+                split_at = start;
+                pointer = Some(offset);
+                break;
+            }
+        }
+
+        for i in 0..split_at {
+            let offset = response.len();
+            if offset <= 0x3FFF {
+                self.offsets.entry(labels[i..].join(".")).or_insert(offset as u16);
+            }
+            let label = labels[i];
+            response.push(label.len() as u8);
+            response.extend_from_slice(label.as_bytes());
+        }
+
+        match pointer {
+            Some(offset) => {
+                response.push(0xC0 | ((offset >> 8) as u8));
+                response.push((offset & 0xFF) as u8);
+            }
+            None => response.push(0),
+        }
+    }
+}
+
+// Parse a resource record (answer/authority/additional section entry),
+// returning its name, type, class, TTL and raw RDATA.
+fn parse_resource_record(buffer: &[u8], offset: &mut usize) -> Option<(String, u16, u16, u32, Vec<u8>)> {
+    let name = parse_domain_name(buffer, offset)?;
+
+    if *offset + 10 > buffer.len() {
+        return None;
+    }
+
+    let rtype = u16::from_be_bytes([buffer[*offset], buffer[*offset + 1]]);
+    let rclass = u16::from_be_bytes([buffer[*offset + 2], buffer[*offset + 3]]);
+    let ttl = u32::from_be_bytes([
+        buffer[*offset + 4],
+        buffer[*offset + 5],
+        buffer[*offset + 6],
+        buffer[*offset + 7],
+    ]);
+    let rdlength = u16::from_be_bytes([buffer[*offset + 8], buffer[*offset + 9]]) as usize;
+    *offset += 10;
+
+    if *offset + rdlength > buffer.len() {
+        return None;
+    }
+
+    let rdata = buffer[*offset..*offset + rdlength].to_vec();
+    *offset += rdlength;
+
+    Some((name, rtype, rclass, ttl, rdata))
+}
+
+// Write one answer RR, compressing `owner_name` (and any domain names in
+// its RDATA) against names already written earlier in the packet.
+fn write_answer(response: &mut Vec<u8>, names: &mut NameWriter, owner_name: &str, rtype: u16, ttl: u32, rdata: &dyn RData) {
+    names.write(owner_name, response);
+    response.extend_from_slice(&rtype.to_be_bytes());
+    response.extend_from_slice(&1u16.to_be_bytes()); // Class (IN)
+    response.extend_from_slice(&ttl.to_be_bytes());
+
+    let rdlength_pos = response.len();
+    response.extend_from_slice(&0u16.to_be_bytes()); // RDLENGTH placeholder
+    let rdata_start = response.len();
+    rdata.write_rdata(response, names);
+    let rdata_len = (response.len() - rdata_start) as u16;
+    response[rdlength_pos..rdlength_pos + 2].copy_from_slice(&rdata_len.to_be_bytes());
+}
+
+// Write one answer RR whose RDATA is already-encoded raw bytes (e.g. a
+// cached upstream answer), with no further name compression inside it.
+fn write_raw_answer(response: &mut Vec<u8>, names: &mut NameWriter, owner_name: &str, rtype: u16, ttl: u32, rdata: &[u8]) {
+    names.write(owner_name, response);
+    response.extend_from_slice(&rtype.to_be_bytes());
+    response.extend_from_slice(&1u16.to_be_bytes()); // Class (IN)
+    response.extend_from_slice(&ttl.to_be_bytes());
+    response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    response.extend_from_slice(rdata);
+}
+
+// Look for a client EDNS0 OPT pseudo-record (TYPE 41) in the query's
+// additional section and return the UDP payload size it advertises, read
+// from the record's CLASS field (RFC 6891 section 6.1.2).
+fn parse_edns_size(query_buffer: &[u8]) -> Option<u16> {
+    let header = DnsHeader::parse(query_buffer)?;
+
+    let mut offset = 12;
+    parse_question(query_buffer, &mut offset)?;
 
-    for part in name.split('.') {
-        if !part.is_empty() {
-            bytes.push(part.len() as u8);
-            bytes.extend_from_slice(part.as_bytes());
+    for _ in 0..header.answer_count {
+        parse_resource_record(query_buffer, &mut offset)?;
+    }
+    for _ in 0..header.authority_count {
+        parse_resource_record(query_buffer, &mut offset)?;
+    }
+    for _ in 0..header.additional_count {
+        let (name, rtype, rclass, _ttl, _rdata) = parse_resource_record(query_buffer, &mut offset)?;
+        if rtype == TYPE_OPT && name.is_empty() {
+            return Some(rclass);
         }
     }
 
-    bytes.push(0); // Null terminator
-    bytes
+    None
+}
+
+// Append our own EDNS0 OPT record (root name, zero RDLENGTH) advertising
+// the UDP payload size we're willing to send.
+fn write_opt_additional(response: &mut Vec<u8>, udp_size: u16) {
+    response.push(0); // root name
+    response.extend_from_slice(&TYPE_OPT.to_be_bytes());
+    response.extend_from_slice(&udp_size.to_be_bytes());
+    response.extend_from_slice(&0u32.to_be_bytes()); // extended RCODE, version, flags
+    response.extend_from_slice(&0u16.to_be_bytes()); // RDLENGTH
+}
+
+// Write the zone's SOA record into the authority section, compressing its
+// owner name and embedded MNAME/RNAME against names written earlier.
+fn write_soa_authority(response: &mut Vec<u8>, names: &mut NameWriter, zone: &Zone, ttl: u32) {
+    write_answer(response, names, &zone.domain, TYPE_SOA, ttl, &zone.soa());
+}
+
+// Forward a raw query to an upstream resolver and return its raw response.
+fn forward_to_upstream(query: &[u8], upstream: SocketAddr) -> Option<Vec<u8>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+    socket.send_to(query, upstream).ok()?;
+
+    let mut buffer = [0u8; 4096];
+    let (size, _) = socket.recv_from(&mut buffer).ok()?;
+    Some(buffer[..size].to_vec())
 }
 
 // Parse DNS question section
@@ -128,85 +677,461 @@ fn parse_question(buffer: &[u8], offset: &mut usize) -> Option<DnsQuestion> {
     })
 }
 
-// Create DNS response
-fn create_response(query_buffer: &[u8], _query_len: usize, records: &HashMap<String, [u8; 4]>) -> Option<Vec<u8>> {
-    let header = DnsHeader::parse(query_buffer)?;
-
-    let mut offset = 12;
-    let question = parse_question(query_buffer, &mut offset)?;
+// Answer a query from the resolver cache or by forwarding it upstream.
+// Returns `None` if recursion is disabled, or if nothing is cached yet and
+// the upstream resolver could not be reached, in which case the caller falls
+// back to its normal NXDOMAIN/SERVFAIL handling. The cache mutex is only
+// held for the lookup and the final insert, never across the blocking
+// upstream round trip, so one slow/unreachable upstream can't stall every
+// other in-flight query sharing the same cache.
+fn resolve_recursively(
+    query_buffer: &[u8],
+    header: &DnsHeader,
+    question: &DnsQuestion,
+    resolver: &ResolverConfig,
+    cache: &Mutex<ResolverCache>,
+    cache_key: &(String, u16),
+    edns_size: Option<u16>,
+) -> Option<Vec<u8>> {
+    let upstream = resolver.upstream?;
 
-    println!("Received query for: {} (type: {})", question.name, question.qtype);
+    let cached = {
+        let mut cache = cache.lock().unwrap();
+        match cache.get(cache_key) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.clone()),
+            Some(_) => {
+                cache.remove(cache_key);
+                None
+            }
+            None => None,
+        }
+    };
 
-    // Only handle A records (type 1)
-    if question.qtype != 1 {
-        println!("  -> Query type not supported (only A records)");
-        return None;
-    }
+    if let Some(entry) = cached {
+        let remaining_ttl = entry.expires_at.duration_since(Instant::now()).as_secs() as u32;
+        println!("  -> Serving {} answer(s) from cache (ttl {}s)", entry.answers.len(), remaining_ttl);
 
-    // Look up the IP address for the domain
-    let ip_address = if let Some(ip) = records.get(&question.name) {
-        println!("  -> Found A record: {}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3]);
-        *ip
-    } else {
-        println!("  -> Domain not found, returning NXDOMAIN");
-        // Return NXDOMAIN (name error)
+        let response_flags = DnsFlags {
+            qr: true,
+            rd: true,
+            ra: true,
+            rcode: RCODE_NO_ERROR,
+            ..Default::default()
+        };
         let response_header = DnsHeader {
             id: header.id,
-            flags: 0x8183, // Response with NXDOMAIN error
+            flags: response_flags.to_u16(),
             question_count: 1,
-            answer_count: 0,
+            answer_count: entry.answers.len() as u16,
             authority_count: 0,
-            additional_count: 0,
+            additional_count: if edns_size.is_some() { 1 } else { 0 },
         };
 
         let mut response = response_header.to_bytes();
-        let name_bytes = encode_domain_name(&question.name);
-        response.extend_from_slice(&name_bytes);
+        let mut names = NameWriter::new();
+        names.write(&question.name, &mut response);
         response.extend_from_slice(&question.qtype.to_be_bytes());
         response.extend_from_slice(&question.qclass.to_be_bytes());
 
+        for (name, rtype, rdata) in &entry.answers {
+            write_raw_answer(&mut response, &mut names, name, *rtype, remaining_ttl, rdata);
+        }
+
+        if edns_size.is_some() {
+            write_opt_additional(&mut response, OUR_EDNS_UDP_SIZE);
+        }
+
         return Some(response);
+    }
+
+    println!("  -> Forwarding query to upstream resolver {}", upstream);
+    let mut upstream_response = forward_to_upstream(query_buffer, upstream)?;
+
+    // Rewrite the transaction ID to match the client's original query.
+    if upstream_response.len() >= 2 {
+        upstream_response[0..2].copy_from_slice(&header.id.to_be_bytes());
+    }
+
+    if let Some(upstream_header) = DnsHeader::parse(&upstream_response) {
+        let mut offset = 12;
+        for _ in 0..upstream_header.question_count {
+            if parse_question(&upstream_response, &mut offset).is_none() {
+                break;
+            }
+        }
+
+        // Only cache records with no embedded name: their RDATA is safe to
+        // replay verbatim, and keeping each one's real owner name (rather
+        // than the query name) keeps a CNAME chain's target records
+        // attributed correctly on a cache hit.
+        let mut cached_answers = Vec::new();
+        let mut min_ttl: Option<u32> = None;
+        for _ in 0..upstream_header.answer_count {
+            match parse_resource_record(&upstream_response, &mut offset) {
+                Some((name, rtype, _rclass, ttl, rdata)) => {
+                    min_ttl = Some(min_ttl.map_or(ttl, |current: u32| current.min(ttl)));
+                    if !rdata_embeds_name(rtype) {
+                        cached_answers.push((name, rtype, rdata));
+                    }
+                }
+                None => break,
+            }
+        }
+
+        if !cached_answers.is_empty() {
+            let ttl = min_ttl.unwrap_or(0);
+            let mut cache = cache.lock().unwrap();
+            cache.insert(
+                cache_key.clone(),
+                CacheEntry {
+                    answers: cached_answers,
+                    expires_at: Instant::now() + Duration::from_secs(ttl as u64),
+                },
+            );
+        }
+    }
+
+    Some(upstream_response)
+}
+
+// Create DNS response
+fn create_response(
+    query_buffer: &[u8],
+    _query_len: usize,
+    zones: &[Zone],
+    resolver: &ResolverConfig,
+    cache: &Mutex<ResolverCache>,
+) -> Option<Vec<u8>> {
+    let header = DnsHeader::parse(query_buffer)?;
+
+    let mut offset = 12;
+    let question = parse_question(query_buffer, &mut offset)?;
+
+    println!("Received query for: {} (type: {})", question.name, question.qtype);
+
+    let request_flags = DnsFlags::from_u16(header.flags);
+    let edns_size = parse_edns_size(query_buffer);
+
+    let cache_key = (question.name.clone(), question.qtype);
+    let zone = find_zone(zones, &question.name);
+
+    // If there's no record of the queried type but the name has a CNAME,
+    // real authoritative servers return the CNAME itself rather than NXDOMAIN.
+    let exact = zone.and_then(|z| z.records.get(&cache_key)).filter(|a| !a.is_empty());
+    let cname = || {
+        if question.qtype == TYPE_CNAME {
+            return None;
+        }
+        let cname_key = (question.name.clone(), TYPE_CNAME);
+        zone.and_then(|z| z.records.get(&cname_key)).filter(|a| !a.is_empty())
     };
 
+    let answers = match exact.or_else(cname) {
+        Some(answers) => answers,
+        None => {
+            if let Some(zone) = zone {
+                println!("  -> No {} record for {}, returning NXDOMAIN with SOA", question.qtype, question.name);
+                let response_flags = DnsFlags {
+                    qr: true,
+                    opcode: request_flags.opcode,
+                    aa: true,
+                    rd: request_flags.rd,
+                    ra: resolver.upstream.is_some(),
+                    rcode: RCODE_NAME_ERROR,
+                    ..Default::default()
+                };
+                let response_header = DnsHeader {
+                    id: header.id,
+                    flags: response_flags.to_u16(),
+                    question_count: 1,
+                    answer_count: 0,
+                    authority_count: 1,
+                    additional_count: if edns_size.is_some() { 1 } else { 0 },
+                };
+
+                let mut response = response_header.to_bytes();
+                let mut names = NameWriter::new();
+                names.write(&question.name, &mut response);
+                response.extend_from_slice(&question.qtype.to_be_bytes());
+                response.extend_from_slice(&question.qclass.to_be_bytes());
+                write_soa_authority(&mut response, &mut names, zone, zone.minimum);
+                if edns_size.is_some() {
+                    write_opt_additional(&mut response, OUR_EDNS_UDP_SIZE);
+                }
+
+                return Some(response);
+            }
+
+            if request_flags.rd {
+                if let Some(response) = resolve_recursively(query_buffer, &header, &question, resolver, cache, &cache_key, edns_size) {
+                    return Some(response);
+                }
+
+                if resolver.upstream.is_some() {
+                    println!("  -> Upstream resolver unreachable, returning SERVFAIL");
+                    let response_flags = DnsFlags {
+                        qr: true,
+                        opcode: request_flags.opcode,
+                        aa: false,
+                        rd: request_flags.rd,
+                        ra: true,
+                        rcode: RCODE_SERVER_FAILURE,
+                        ..Default::default()
+                    };
+                    let response_header = DnsHeader {
+                        id: header.id,
+                        flags: response_flags.to_u16(),
+                        question_count: 1,
+                        answer_count: 0,
+                        authority_count: 0,
+                        additional_count: if edns_size.is_some() { 1 } else { 0 },
+                    };
+
+                    let mut response = response_header.to_bytes();
+                    let mut names = NameWriter::new();
+                    names.write(&question.name, &mut response);
+                    response.extend_from_slice(&question.qtype.to_be_bytes());
+                    response.extend_from_slice(&question.qclass.to_be_bytes());
+                    if edns_size.is_some() {
+                        write_opt_additional(&mut response, OUR_EDNS_UDP_SIZE);
+                    }
+
+                    return Some(response);
+                }
+            }
+
+            println!("  -> Domain not found, returning NXDOMAIN");
+            // Return NXDOMAIN (name error). No zone covers this name, so
+            // we're not authoritative for it.
+            let response_flags = DnsFlags {
+                qr: true,
+                opcode: request_flags.opcode,
+                aa: false,
+                rd: request_flags.rd,
+                ra: resolver.upstream.is_some(),
+                rcode: RCODE_NAME_ERROR,
+                ..Default::default()
+            };
+            let response_header = DnsHeader {
+                id: header.id,
+                flags: response_flags.to_u16(),
+                question_count: 1,
+                answer_count: 0,
+                authority_count: 0,
+                additional_count: if edns_size.is_some() { 1 } else { 0 },
+            };
+
+            let mut response = response_header.to_bytes();
+            let mut names = NameWriter::new();
+            names.write(&question.name, &mut response);
+            response.extend_from_slice(&question.qtype.to_be_bytes());
+            response.extend_from_slice(&question.qclass.to_be_bytes());
+            if edns_size.is_some() {
+                write_opt_additional(&mut response, OUR_EDNS_UDP_SIZE);
+            }
+
+            return Some(response);
+        }
+    };
+
+    println!("  -> Found {} record(s)", answers.len());
+
     // Create response header
+    let response_flags = DnsFlags {
+        qr: true,
+        opcode: request_flags.opcode,
+        aa: true,
+        rd: request_flags.rd,
+        ra: resolver.upstream.is_some(),
+        rcode: RCODE_NO_ERROR,
+        ..Default::default()
+    };
     let response_header = DnsHeader {
         id: header.id,
-        flags: 0x8180, // Standard query response, no error
+        flags: response_flags.to_u16(),
         question_count: 1,
-        answer_count: 1,
+        answer_count: answers.len() as u16,
         authority_count: 0,
-        additional_count: 0,
+        additional_count: if edns_size.is_some() { 1 } else { 0 },
     };
 
     let mut response = response_header.to_bytes();
 
     // Add question section (echo back)
-    let name_bytes = encode_domain_name(&question.name);
-    response.extend_from_slice(&name_bytes);
+    let mut names = NameWriter::new();
+    names.write(&question.name, &mut response);
     response.extend_from_slice(&question.qtype.to_be_bytes());
     response.extend_from_slice(&question.qclass.to_be_bytes());
 
-    // Add answer section (A record)
-    // Name (pointer to question)
-    response.push(0xC0);
-    response.push(0x0C);
+    // Add one answer per matching record, each with its own zone-file TTL
+    for (ttl, answer) in answers {
+        write_answer(&mut response, &mut names, &question.name, answer.rtype(), *ttl, answer.as_ref());
+    }
 
-    // Type (A record)
-    response.extend_from_slice(&1u16.to_be_bytes());
+    if edns_size.is_some() {
+        write_opt_additional(&mut response, OUR_EDNS_UDP_SIZE);
+    }
 
-    // Class (IN)
-    response.extend_from_slice(&1u16.to_be_bytes());
+    Some(response)
+}
 
-    // TTL (300 seconds)
-    response.extend_from_slice(&300u32.to_be_bytes());
+// Parse `--upstream <addr>` / `--no-recursion` from the command line.
+// Defaults to forwarding to Google's public resolver.
+fn parse_resolver_config() -> ResolverConfig {
+    let args: Vec<String> = std::env::args().collect();
+    let mut upstream_addr = "8.8.8.8:53".to_string();
+    let mut no_recursion = false;
 
-    // Data length (4 bytes for IPv4)
-    response.extend_from_slice(&4u16.to_be_bytes());
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--no-recursion" => no_recursion = true,
+            "--upstream" => {
+                if let Some(value) = args.get(i + 1) {
+                    upstream_addr = value.clone();
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
 
-    // IP address from our records
-    response.extend_from_slice(&ip_address);
+    if no_recursion {
+        return ResolverConfig { upstream: None };
+    }
 
-    Some(response)
+    match upstream_addr.parse::<SocketAddr>() {
+        Ok(addr) => ResolverConfig { upstream: Some(addr) },
+        Err(e) => {
+            eprintln!("Invalid --upstream address '{}': {}, disabling recursion", upstream_addr, e);
+            ResolverConfig { upstream: None }
+        }
+    }
+}
+
+// Parse `--zone-file <path>` from the command line, defaulting to zones.txt.
+fn parse_zone_file_path() -> String {
+    let args: Vec<String> = std::env::args().collect();
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == "--zone-file" {
+            if let Some(value) = args.get(i + 1) {
+                return value.clone();
+            }
+        }
+        i += 1;
+    }
+    "zones.txt".to_string()
+}
+
+// Truncate an oversized UDP response to the wire limit and set the TC bit
+// so the client knows to retry the query over TCP. The limit is the
+// client's advertised EDNS0 UDP payload size when present, else 512; an
+// advertised size below 512 is raised to 512.
+//
+// RRs are dropped whole, never sliced mid-record: we walk the answer,
+// authority and additional sections counting how many complete RRs fit
+// within `max_size`, truncate the buffer right after the last one that
+// does, and rewrite the header counts to match what's actually present.
+fn truncate_for_udp(response: &mut Vec<u8>, query_buffer: &[u8]) {
+    let max_size = parse_edns_size(query_buffer)
+        .map(|size| size as usize)
+        .unwrap_or(MAX_UDP_RESPONSE_SIZE)
+        .max(MAX_UDP_RESPONSE_SIZE);
+
+    if response.len() <= max_size {
+        return;
+    }
+
+    let Some(header) = DnsHeader::parse(response) else {
+        return;
+    };
+    let mut offset = 12;
+    for _ in 0..header.question_count {
+        if parse_question(response, &mut offset).is_none() {
+            return;
+        }
+    }
+
+    let mut counts = [0u16; 3];
+    let sections = [header.answer_count, header.authority_count, header.additional_count];
+    'sections: for (section, &section_count) in sections.iter().enumerate() {
+        for _ in 0..section_count {
+            let mut next_offset = offset;
+            if parse_resource_record(response, &mut next_offset).is_none() || next_offset > max_size {
+                break 'sections;
+            }
+            offset = next_offset;
+            counts[section] += 1;
+        }
+        if counts[section] < section_count {
+            break;
+        }
+    }
+
+    response.truncate(offset);
+    response[6..8].copy_from_slice(&counts[0].to_be_bytes());
+    response[8..10].copy_from_slice(&counts[1].to_be_bytes());
+    response[10..12].copy_from_slice(&counts[2].to_be_bytes());
+
+    if response.len() >= 4 {
+        let mut flags = DnsFlags::from_u16(u16::from_be_bytes([response[2], response[3]]));
+        flags.tc = true;
+        response[2..4].copy_from_slice(&flags.to_u16().to_be_bytes());
+    }
+}
+
+// Serve one TCP connection: each query is framed with a 2-byte big-endian
+// length prefix, and so is each response (RFC 1035 section 4.2.2).
+fn handle_tcp_connection(
+    mut stream: TcpStream,
+    zones: Arc<Vec<Zone>>,
+    resolver: ResolverConfig,
+    cache: Arc<Mutex<ResolverCache>>,
+) {
+    loop {
+        let mut length_prefix = [0u8; 2];
+        if stream.read_exact(&mut length_prefix).is_err() {
+            return;
+        }
+        let query_len = u16::from_be_bytes(length_prefix) as usize;
+
+        let mut query_buffer = vec![0u8; query_len];
+        if stream.read_exact(&mut query_buffer).is_err() {
+            return;
+        }
+
+        let response = create_response(&query_buffer, query_len, &zones, &resolver, &cache);
+
+        let Some(response) = response else {
+            return;
+        };
+
+        let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&response);
+        if stream.write_all(&framed).is_err() {
+            return;
+        }
+    }
+}
+
+fn run_tcp_server(zones: Arc<Vec<Zone>>, resolver: ResolverConfig, cache: Arc<Mutex<ResolverCache>>) -> Result<()> {
+    let listener = TcpListener::bind("0.0.0.0:53000")?;
+    println!("DNS Server is also listening for TCP queries on 0.0.0.0:53000");
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let zones = Arc::clone(&zones);
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || handle_tcp_connection(stream, zones, resolver, cache));
+            }
+            Err(e) => eprintln!("Failed to accept TCP connection: {}", e),
+        }
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -214,19 +1139,33 @@ fn main() -> Result<()> {
     println!("Press Ctrl+C to stop");
     println!("---");
 
-    // Configure A records (domain -> IP address mapping)
-    let mut records: HashMap<String, [u8; 4]> = HashMap::new();
-    records.insert("example.com".to_string(), [93, 184, 216, 34]);  // example.com -> 93.184.216.34
-    records.insert("test.local".to_string(), [192, 168, 1, 100]);    // test.local -> 192.168.1.100
-    records.insert("myserver.local".to_string(), [10, 0, 0, 50]);    // myserver.local -> 10.0.0.50
-    records.insert("localhost".to_string(), [127, 0, 0, 1]);         // localhost -> 127.0.0.1
+    let zone_file_path = parse_zone_file_path();
+    let zones = load_zones(&zone_file_path)?;
+    println!("Loaded {} zone(s) from {}", zones.len(), zone_file_path);
+    for zone in &zones {
+        println!("  {} (serial {})", zone.domain, zone.serial);
+    }
 
-    println!("Configured A records:");
-    for (domain, ip) in &records {
-        println!("  {} -> {}.{}.{}.{}", domain, ip[0], ip[1], ip[2], ip[3]);
+    let resolver = parse_resolver_config();
+    match resolver.upstream {
+        Some(addr) => println!("Recursion enabled, forwarding to {}", addr),
+        None => println!("Recursion disabled"),
     }
     println!("---");
 
+    let zones = Arc::new(zones);
+    let cache: Arc<Mutex<ResolverCache>> = Arc::new(Mutex::new(HashMap::new()));
+
+    {
+        let zones = Arc::clone(&zones);
+        let cache = Arc::clone(&cache);
+        thread::spawn(move || {
+            if let Err(e) = run_tcp_server(zones, resolver, cache) {
+                eprintln!("TCP server failed: {}", e);
+            }
+        });
+    }
+
     let socket = UdpSocket::bind("0.0.0.0:53000")?;
     let mut buffer = [0u8; 512];
 
@@ -239,7 +1178,10 @@ fn main() -> Result<()> {
             Ok((size, source)) => {
                 println!("\nReceived {} bytes from {}", size, source);
 
-                if let Some(response) = create_response(&buffer, size, &records) {
+                let response = create_response(&buffer, size, &zones, &resolver, &cache);
+
+                if let Some(mut response) = response {
+                    truncate_for_udp(&mut response, &buffer);
                     match socket.send_to(&response, source) {
                         Ok(_) => println!("Sent response to {}", source),
                         Err(e) => eprintln!("Failed to send response: {}", e),